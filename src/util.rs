@@ -15,8 +15,7 @@ use packet::ip::IpNextHeaderProtocol;
 use pnet_macros_support::types::u16be;
 
 use std::fmt;
-use std::net::{Ipv4Addr, Ipv6Addr};
-use std::slice;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::str::FromStr;
 use std::u8;
 
@@ -29,6 +28,48 @@ impl MacAddr {
     pub fn new(a: u8, b: u8, c: u8, d: u8, e: u8, f: u8) -> MacAddr {
         MacAddr(a, b, c, d, e, f)
     }
+
+    /// The broadcast MAC address, ff:ff:ff:ff:ff:ff.
+    pub fn broadcast() -> MacAddr {
+        MacAddr(0xff, 0xff, 0xff, 0xff, 0xff, 0xff)
+    }
+
+    /// The all-zero MAC address, 00:00:00:00:00:00.
+    pub fn zero() -> MacAddr {
+        MacAddr(0, 0, 0, 0, 0, 0)
+    }
+
+    /// Is this `MacAddr::broadcast()`?
+    pub fn is_broadcast(&self) -> bool {
+        *self == MacAddr::broadcast()
+    }
+
+    /// Is this `MacAddr::zero()`?
+    pub fn is_zero(&self) -> bool {
+        *self == MacAddr::zero()
+    }
+
+    /// Is this a multicast address? Multicast (and broadcast) addresses have the low
+    /// bit of the first octet set.
+    pub fn is_multicast(&self) -> bool {
+        self.0 & 0x01 != 0
+    }
+
+    /// Is this a unicast address, ie. neither multicast nor broadcast?
+    pub fn is_unicast(&self) -> bool {
+        !self.is_multicast()
+    }
+
+    /// Is this a locally administered address? This is determined by the U/L bit,
+    /// bit 1 of the first octet.
+    pub fn is_local(&self) -> bool {
+        self.0 & 0x02 != 0
+    }
+
+    /// Is this a universally administered (vendor assigned, burned-in) address?
+    pub fn is_universal(&self) -> bool {
+        !self.is_local()
+    }
 }
 
 impl PrimitiveValues for MacAddr {
@@ -69,33 +110,99 @@ pub enum ParseMacAddrErr {
     TooFewComponents,
     /// One of the components contains an invalid value, eg. 00:GG:22:33:44:55
     InvalidComponent,
+    /// The address mixes more than one kind of separator, eg. 00:11-22:33:44:55
+    InvalidSeparator,
 }
 
 impl FromStr for MacAddr {
     type Err = ParseMacAddrErr;
     fn from_str(s: &str) -> Result<MacAddr, ParseMacAddrErr> {
-        let mut parts = [0u8; 6];
-        let splits = s.split(':');
-        let mut i = 0;
-        for split in splits {
-            if i == 6 {
-                return Err(ParseMacAddrErr::TooManyComponents);
-            }
-            match u8::from_str_radix(split, 16) {
-                Ok(b) if split.len() != 0 => parts[i] = b,
-                _ => return Err(ParseMacAddrErr::InvalidComponent),
-            }
-            i += 1;
+        // Auto-detect the notation in use: colon-separated (00:11:22:33:44:55), IEEE
+        // hyphen-separated (00-11-22-33-44-55), Cisco dotted (0011.2233.4455), or bare
+        // hex (001122334455).
+        match (s.contains(':'), s.contains('-'), s.contains('.')) {
+            (true, false, false) => parse_mac_addr_separated(s, ':'),
+            (false, true, false) => parse_mac_addr_separated(s, '-'),
+            (false, false, true) => parse_mac_addr_dotted(s),
+            (false, false, false) => parse_mac_addr_bare(s),
+            _ => Err(ParseMacAddrErr::InvalidSeparator),
         }
+    }
+}
 
+/// Parse a MAC address made up of 6 hex components joined by `sep`, eg. the
+/// colon-separated `00:11:22:33:44:55` or hyphen-separated `00-11-22-33-44-55` forms.
+fn parse_mac_addr_separated(s: &str, sep: char) -> Result<MacAddr, ParseMacAddrErr> {
+    let mut parts = [0u8; 6];
+    let splits = s.split(sep);
+    let mut i = 0;
+    for split in splits {
         if i == 6 {
-            Ok(MacAddr(parts[0], parts[1], parts[2], parts[3], parts[4], parts[5]))
-        } else {
-            Err(ParseMacAddrErr::TooFewComponents)
+            return Err(ParseMacAddrErr::TooManyComponents);
+        }
+        match u8::from_str_radix(split, 16) {
+            Ok(b) if split.len() != 0 => parts[i] = b,
+            _ => return Err(ParseMacAddrErr::InvalidComponent),
         }
+        i += 1;
+    }
+
+    if i == 6 {
+        Ok(MacAddr(parts[0], parts[1], parts[2], parts[3], parts[4], parts[5]))
+    } else {
+        Err(ParseMacAddrErr::TooFewComponents)
     }
 }
 
+/// Parse the Cisco dotted-quad form, eg. `0011.2233.4455`: 3 components of 4 hex
+/// digits each, every component holding 2 bytes of the address.
+fn parse_mac_addr_dotted(s: &str) -> Result<MacAddr, ParseMacAddrErr> {
+    let mut parts = [0u8; 6];
+    let splits = s.split('.');
+    let mut i = 0;
+    for split in splits {
+        if i == 3 {
+            return Err(ParseMacAddrErr::TooManyComponents);
+        }
+        match u16::from_str_radix(split, 16) {
+            Ok(word) if split.len() == 4 => {
+                parts[i * 2] = (word >> 8) as u8;
+                parts[i * 2 + 1] = word as u8;
+            }
+            _ => return Err(ParseMacAddrErr::InvalidComponent),
+        }
+        i += 1;
+    }
+
+    if i == 3 {
+        Ok(MacAddr(parts[0], parts[1], parts[2], parts[3], parts[4], parts[5]))
+    } else {
+        Err(ParseMacAddrErr::TooFewComponents)
+    }
+}
+
+/// Parse the unseparated form, eg. `001122334455`: exactly 12 hex digits, 2 per byte.
+fn parse_mac_addr_bare(s: &str) -> Result<MacAddr, ParseMacAddrErr> {
+    if s.len() > 12 {
+        return Err(ParseMacAddrErr::TooManyComponents);
+    } else if s.len() < 12 {
+        return Err(ParseMacAddrErr::TooFewComponents);
+    }
+    // Reject anything that isn't an ASCII hex digit before slicing by byte offset
+    // below - otherwise a non-ASCII character (which can make `s.len()` count more
+    // bytes than chars) could leave a byte offset sitting mid-character and panic.
+    if !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(ParseMacAddrErr::InvalidComponent);
+    }
+
+    let mut parts = [0u8; 6];
+    for (i, part) in parts.iter_mut().enumerate() {
+        *part = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16)
+            .expect("already validated as ASCII hex digits");
+    }
+    Ok(MacAddr(parts[0], parts[1], parts[2], parts[3], parts[4], parts[5]))
+}
+
 #[test]
 fn mac_addr_from_str() {
     assert_eq!("00:00:00:00:00:00".parse(), Ok(MacAddr(0, 0, 0, 0, 0, 0)));
@@ -123,6 +230,50 @@ fn mac_addr_from_str() {
                Err(ParseMacAddrErr::InvalidComponent));
 }
 
+#[test]
+fn mac_addr_from_str_dash() {
+    assert_eq!("00-11-22-33-44-55".parse(),
+               Ok(MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55)));
+    assert_eq!("12-34-56-78".parse::<MacAddr>(),
+               Err(ParseMacAddrErr::TooFewComponents));
+}
+
+#[test]
+fn mac_addr_from_str_dotted() {
+    assert_eq!("0011.2233.4455".parse(),
+               Ok(MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55)));
+    assert_eq!("0011.2233".parse::<MacAddr>(),
+               Err(ParseMacAddrErr::TooFewComponents));
+    assert_eq!("0011.2233.4455.6677".parse::<MacAddr>(),
+               Err(ParseMacAddrErr::TooManyComponents));
+    assert_eq!("011.2233.4455".parse::<MacAddr>(),
+               Err(ParseMacAddrErr::InvalidComponent));
+}
+
+#[test]
+fn mac_addr_from_str_bare() {
+    assert_eq!("001122334455".parse(),
+               Ok(MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55)));
+    assert_eq!("0011223344".parse::<MacAddr>(),
+               Err(ParseMacAddrErr::TooFewComponents));
+    assert_eq!("00112233445566".parse::<MacAddr>(),
+               Err(ParseMacAddrErr::TooManyComponents));
+    assert_eq!("0011223344gg".parse::<MacAddr>(),
+               Err(ParseMacAddrErr::InvalidComponent));
+    // 9 ASCII bytes + a 2-byte UTF-8 'é' + 1 ASCII byte = 12 bytes but 11 chars, so a
+    // byte-offset slice that assumed one byte per char would land mid-character.
+    assert_eq!("001122334\u{e9}5".parse::<MacAddr>(),
+               Err(ParseMacAddrErr::InvalidComponent));
+}
+
+#[test]
+fn mac_addr_from_str_mixed_separator() {
+    assert_eq!("00:11-22:33:44:55".parse::<MacAddr>(),
+               Err(ParseMacAddrErr::InvalidSeparator));
+    assert_eq!("00:11.2233:44:55".parse::<MacAddr>(),
+               Err(ParseMacAddrErr::InvalidSeparator));
+}
+
 #[test]
 fn str_from_mac_addr() {
     assert_eq!(format!("{}", MacAddr(0, 0, 0, 0, 0, 0)),
@@ -133,6 +284,30 @@ fn str_from_mac_addr() {
                "12:34:56:78:09:ab");
 }
 
+#[test]
+fn mac_addr_classification() {
+    assert!(MacAddr::broadcast().is_broadcast());
+    assert!(MacAddr::broadcast().is_multicast());
+    assert!(!MacAddr::broadcast().is_unicast());
+
+    assert!(MacAddr::zero().is_zero());
+    assert!(MacAddr::zero().is_unicast());
+    assert!(MacAddr::zero().is_universal());
+    assert!(!MacAddr::zero().is_local());
+
+    // 01:00:5e:00:00:01 - an IPv4 multicast address, not the broadcast address
+    let multicast = MacAddr(0x01, 0x00, 0x5e, 0x00, 0x00, 0x01);
+    assert!(multicast.is_multicast());
+    assert!(!multicast.is_broadcast());
+    assert!(!multicast.is_unicast());
+
+    // 02:00:00:00:00:01 - locally administered, unicast
+    let local = MacAddr(0x02, 0x00, 0x00, 0x00, 0x00, 0x01);
+    assert!(local.is_unicast());
+    assert!(local.is_local());
+    assert!(!local.is_universal());
+}
+
 /// Convert value to byte array
 pub trait Octets {
     /// Output type - bytes array
@@ -181,39 +356,248 @@ impl Octets for u8 {
     }
 }
 
+/// Controls whether a checksum is generated on transmit, verified on receive, both, or
+/// neither, for a single protocol.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Checksum {
+    /// Generate the checksum on transmit and verify it on receive. This is the
+    /// historical, software-only behaviour.
+    Both,
+    /// Generate the checksum on transmit, but don't verify it on receive - eg. because
+    /// the receiving hardware already checked it and would drop the packet if it
+    /// didn't match.
+    Tx,
+    /// Verify the checksum on receive, but don't generate it on transmit - the field
+    /// is written as zero instead, for hardware that fills it in itself.
+    Rx,
+    /// Neither generate nor verify the checksum; leave the on-wire field as zero and
+    /// trust the hardware to fill in and check the real value.
+    None,
+}
+
+impl Checksum {
+    /// Should a checksum be computed when building a packet?
+    pub fn should_generate(&self) -> bool {
+        match *self {
+            Checksum::Both | Checksum::Tx => true,
+            Checksum::Rx | Checksum::None => false,
+        }
+    }
+
+    /// Should a checksum be verified when parsing a packet?
+    pub fn should_verify(&self) -> bool {
+        match *self {
+            Checksum::Both | Checksum::Rx => true,
+            Checksum::Tx | Checksum::None => false,
+        }
+    }
+}
+
+/// Describes, per protocol, whether checksums should be generated and/or verified in
+/// software. Many NICs - and embedded MACs such as those on the STM32 - generate
+/// and/or verify checksums themselves, and some actually require the on-wire field to
+/// be zero when they do; computing it again in software wastes cycles at best and
+/// corrupts the packet at worst. Pass a `ChecksumCapabilities` through to the packet
+/// builders to describe what the underlying interface already handles.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ChecksumCapabilities {
+    ipv4: Checksum,
+    udp: Checksum,
+    tcp: Checksum,
+    icmpv4: Checksum,
+    icmpv6: Checksum,
+}
+
+impl ChecksumCapabilities {
+    /// Create a new `ChecksumCapabilities` with every protocol computed and verified
+    /// in software.
+    pub fn new() -> ChecksumCapabilities {
+        ChecksumCapabilities::default()
+    }
+
+    /// IPv4 header checksum handling.
+    pub fn ipv4(&self) -> Checksum {
+        self.ipv4
+    }
+
+    /// Set IPv4 header checksum handling.
+    pub fn set_ipv4(&mut self, checksum: Checksum) {
+        self.ipv4 = checksum;
+    }
+
+    /// UDP checksum handling.
+    pub fn udp(&self) -> Checksum {
+        self.udp
+    }
+
+    /// Set UDP checksum handling.
+    pub fn set_udp(&mut self, checksum: Checksum) {
+        self.udp = checksum;
+    }
+
+    /// TCP checksum handling.
+    pub fn tcp(&self) -> Checksum {
+        self.tcp
+    }
+
+    /// Set TCP checksum handling.
+    pub fn set_tcp(&mut self, checksum: Checksum) {
+        self.tcp = checksum;
+    }
+
+    /// ICMPv4 checksum handling.
+    pub fn icmpv4(&self) -> Checksum {
+        self.icmpv4
+    }
+
+    /// Set ICMPv4 checksum handling.
+    pub fn set_icmpv4(&mut self, checksum: Checksum) {
+        self.icmpv4 = checksum;
+    }
+
+    /// ICMPv6 checksum handling.
+    pub fn icmpv6(&self) -> Checksum {
+        self.icmpv6
+    }
+
+    /// Set ICMPv6 checksum handling.
+    pub fn set_icmpv6(&mut self, checksum: Checksum) {
+        self.icmpv6 = checksum;
+    }
+}
+
+impl Default for ChecksumCapabilities {
+    fn default() -> ChecksumCapabilities {
+        ChecksumCapabilities {
+            ipv4: Checksum::Both,
+            udp: Checksum::Both,
+            tcp: Checksum::Both,
+            icmpv4: Checksum::Both,
+            icmpv6: Checksum::Both,
+        }
+    }
+}
+
+#[test]
+fn checksum_capabilities_default_is_both() {
+    let caps = ChecksumCapabilities::new();
+    assert_eq!(caps.ipv4(), Checksum::Both);
+    assert_eq!(caps.udp(), Checksum::Both);
+    assert_eq!(caps.tcp(), Checksum::Both);
+    assert_eq!(caps.icmpv4(), Checksum::Both);
+    assert_eq!(caps.icmpv6(), Checksum::Both);
+}
+
+#[test]
+fn checksum_capabilities_set_per_protocol() {
+    let mut caps = ChecksumCapabilities::new();
+    caps.set_udp(Checksum::Rx);
+    assert_eq!(caps.udp(), Checksum::Rx);
+    assert!(caps.udp().should_verify());
+    assert!(!caps.udp().should_generate());
+    assert_eq!(caps.tcp(), Checksum::Both);
+}
+
 /// Calculates a checksum. Used by ipv4 and icmp. The two bytes starting at `skipword * 2` will be
 /// ignored. Supposed to be the checksum field, which is regarded as zero during calculation.
-pub fn checksum(data: &[u8], skipword: usize) -> u16be {
+///
+/// `cap` controls whether the checksum is actually computed: when it says not to
+/// generate on transmit (eg. because the underlying NIC inserts the checksum itself),
+/// this returns `0` without touching `data`, so the on-wire field is left as the zero
+/// hardware offload expects instead of a software-computed value it would overwrite.
+pub fn checksum(data: &[u8], skipword: usize, cap: Checksum) -> u16be {
+    if !cap.should_generate() {
+        return 0;
+    }
     let sum = sum_be_words(data, skipword);
     finalize_checksum(sum)
 }
 
-fn finalize_checksum(mut sum: u32) -> u16be {
+fn finalize_checksum(mut sum: u64) -> u16be {
     while sum >> 16 != 0 {
         sum = (sum >> 16) + (sum & 0xFFFF);
     }
     !sum as u16
 }
 
-/// Calculate the checksum for a packet built on IPv4. Used by udp and tcp.
-pub fn ipv4_checksum(data: &[u8],
-                     skipword: usize,
-                     extra_data: &[u8],
-                     source: Ipv4Addr,
-                     destination: Ipv4Addr,
-                     next_level_protocol: IpNextHeaderProtocol)
-    -> u16be {
-    let mut sum = 0u32;
+#[test]
+fn checksum_odd_length_and_skipword() {
+    // An odd-length buffer, with the checksum field (word 0) itself covered by
+    // skipword, straddling several of sum_be_words' 4-byte chunks.
+    let data = [0x00, 0x00, 0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0, 0x11];
+    let with_skip = checksum(&data, 0, Checksum::Both);
+    // Skipping word 0 (which is already zero) must give the same result as not
+    // skipping anything.
+    assert_eq!(with_skip, checksum(&data, data.len() / 2, Checksum::Both));
+}
+
+#[test]
+fn checksum_skips_generation_when_capability_says_so() {
+    let data = [0x45u8, 0x00, 0x00, 0x3c, 0x1c, 0x46, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00, 0xc0,
+                0xa8, 0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7];
+    assert_eq!(checksum(&data, 5, Checksum::Rx), 0);
+    assert_eq!(checksum(&data, 5, Checksum::None), 0);
+    assert_ne!(checksum(&data, 5, Checksum::Tx), 0);
+}
+
+/// Returned by `transport_checksum` when `source` and `destination` are not the same
+/// IP address family (eg. one is IPv4 and the other IPv6).
+#[derive(Copy, Debug, PartialEq, Eq, Clone)]
+pub struct MismatchedAddrFamily;
+
+/// Calculate a transport-layer checksum (eg. for UDP or TCP) over the given data and
+/// pseudo-header, dispatching on whether `source`/`destination` are IPv4 or IPv6.
+/// This is the single entry point for callers holding a generic `IpAddr`, rather than
+/// branching between `ipv4_checksum` and `ipv6_checksum` themselves at every call
+/// site. Returns `Err(MismatchedAddrFamily)` if `source` and `destination` are not the
+/// same address family.
+///
+/// `cap` is this protocol's entry from a `ChecksumCapabilities` (eg. `caps.udp()` or
+/// `caps.tcp()`). Address-family validation still happens either way; if `cap` says
+/// not to generate on transmit, `0` is returned instead of computing the checksum, so
+/// the on-wire field is left zero for hardware that fills it in itself.
+pub fn transport_checksum(data: &[u8],
+                          skipword: usize,
+                          extra_data: &[u8],
+                          source: IpAddr,
+                          destination: IpAddr,
+                          next_level_protocol: IpNextHeaderProtocol,
+                          cap: Checksum)
+    -> Result<u16be, MismatchedAddrFamily> {
+    let pseudo_header_sum = match (source, destination) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => {
+            ipv4_word_sum(source) + ipv4_word_sum(destination)
+        }
+        (IpAddr::V6(source), IpAddr::V6(destination)) => {
+            ipv6_word_sum(source) + ipv6_word_sum(destination)
+        }
+        _ => return Err(MismatchedAddrFamily),
+    };
+
+    if !cap.should_generate() {
+        return Ok(0);
+    }
 
-    // Checksum pseudo-header
-    sum += ipv4_word_sum(source);
-    sum += ipv4_word_sum(destination);
+    Ok(finish_transport_checksum(pseudo_header_sum as u64,
+                                  data,
+                                  skipword,
+                                  extra_data,
+                                  next_level_protocol))
+}
+
+fn finish_transport_checksum(pseudo_header_sum: u64,
+                              data: &[u8],
+                              skipword: usize,
+                              extra_data: &[u8],
+                              next_level_protocol: IpNextHeaderProtocol)
+    -> u16be {
+    let mut sum = pseudo_header_sum;
 
     let IpNextHeaderProtocol(next_level_protocol) = next_level_protocol;
-    sum += next_level_protocol as u32;
+    sum += next_level_protocol as u64;
 
     let len = data.len() + extra_data.len();
-    sum += len as u32;
+    sum += len as u64;
 
     // Checksum packet header and data
     sum += sum_be_words(data, skipword);
@@ -222,6 +606,24 @@ pub fn ipv4_checksum(data: &[u8],
     finalize_checksum(sum)
 }
 
+/// Calculate the checksum for a packet built on IPv4. Used by udp and tcp.
+pub fn ipv4_checksum(data: &[u8],
+                     skipword: usize,
+                     extra_data: &[u8],
+                     source: Ipv4Addr,
+                     destination: Ipv4Addr,
+                     next_level_protocol: IpNextHeaderProtocol)
+    -> u16be {
+    transport_checksum(data,
+                        skipword,
+                        extra_data,
+                        IpAddr::V4(source),
+                        IpAddr::V4(destination),
+                        next_level_protocol,
+                        Checksum::Both)
+        .expect("source and destination are both IpAddr::V4")
+}
+
 fn ipv4_word_sum(ip: Ipv4Addr) -> u32 {
     let octets = ip.octets();
     ((octets[0] as u32) << 8 | octets[1] as u32) + ((octets[2] as u32) << 8 | octets[3] as u32)
@@ -235,69 +637,260 @@ pub fn ipv6_checksum(data: &[u8],
                      destination: Ipv6Addr,
                      next_level_protocol: IpNextHeaderProtocol)
     -> u16be {
-    let mut sum = 0u32;
-
-    // Checksum pseudo-header
-    sum += ipv6_word_sum(source);
-    sum += ipv6_word_sum(destination);
+    transport_checksum(data,
+                        skipword,
+                        extra_data,
+                        IpAddr::V6(source),
+                        IpAddr::V6(destination),
+                        next_level_protocol,
+                        Checksum::Both)
+        .expect("source and destination are both IpAddr::V6")
+}
 
-    let IpNextHeaderProtocol(next_level_protocol) = next_level_protocol;
-    sum += next_level_protocol as u32;
+fn ipv6_word_sum(ip: Ipv6Addr) -> u32 {
+    ip.segments().iter().map(|x| *x as u32).sum()
+}
 
-    let len = data.len() + extra_data.len();
-    sum += len as u32;
+#[test]
+fn transport_checksum_matches_ipv4_checksum() {
+    let data = [0u8; 8];
+    let source = Ipv4Addr::new(192, 168, 0, 1);
+    let destination = Ipv4Addr::new(192, 168, 0, 2);
+    let protocol = IpNextHeaderProtocol(17);
+    assert_eq!(transport_checksum(&data,
+                                   0,
+                                   &[],
+                                   IpAddr::V4(source),
+                                   IpAddr::V4(destination),
+                                   protocol,
+                                   Checksum::Both),
+               Ok(ipv4_checksum(&data, 0, &[], source, destination, protocol)));
+}
 
-    // Checksum packet header and data
-    sum += sum_be_words(data, skipword);
-    sum += sum_be_words(extra_data, extra_data.len() / 2);
+#[test]
+fn transport_checksum_matches_ipv6_checksum() {
+    let data = [0u8; 8];
+    let source = Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1);
+    let destination = Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 2);
+    let protocol = IpNextHeaderProtocol(6);
+    assert_eq!(transport_checksum(&data,
+                                   0,
+                                   &[],
+                                   IpAddr::V6(source),
+                                   IpAddr::V6(destination),
+                                   protocol,
+                                   Checksum::Both),
+               Ok(ipv6_checksum(&data, 0, &[], source, destination, protocol)));
+}
 
-    finalize_checksum(sum)
+#[test]
+fn transport_checksum_rejects_mixed_families() {
+    let source = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+    let destination = IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1));
+    assert_eq!(transport_checksum(&[],
+                                   0,
+                                   &[],
+                                   source,
+                                   destination,
+                                   IpNextHeaderProtocol(6),
+                                   Checksum::Both),
+               Err(MismatchedAddrFamily));
 }
 
-fn ipv6_word_sum(ip: Ipv6Addr) -> u32 {
-    ip.segments().iter().map(|x| *x as u32).sum()
+#[test]
+fn transport_checksum_skips_generation_when_capability_says_so() {
+    let data = [0u8; 8];
+    let source = IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1));
+    let destination = IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2));
+    let protocol = IpNextHeaderProtocol(17);
+    assert_eq!(transport_checksum(&data, 0, &[], source, destination, protocol, Checksum::Rx),
+               Ok(0));
+    assert_eq!(transport_checksum(&data, 0, &[], source, destination, protocol, Checksum::None),
+               Ok(0));
 }
 
 /// Sum all words (16 bit chunks) in the given data. The word at word offset
 /// `skipword` will be skipped. Each word is treated as big endian.
-fn sum_be_words(data: &[u8], skipword: usize) -> u32 {
+///
+/// Reads are done 4 bytes at a time via `from_be_bytes` on a fixed-size array, rather
+/// than casting `data` to a `&[u16]`, since that cast is undefined behaviour whenever
+/// `data` isn't 2-byte aligned - which packet buffers routinely aren't. Folding two
+/// words per iteration into a `u64` accumulator also means a `one's complement`
+/// overflow fold only has to happen once, at the end, rather than on every word.
+fn sum_be_words(data: &[u8], skipword: usize) -> u64 {
     let len = data.len();
-    let wdata: &[u16] = unsafe { slice::from_raw_parts(data.as_ptr() as *const u16, len / 2) };
-    assert!(skipword <= wdata.len());
-
-    let mut sum = 0u32;
-    let mut i = 0;
-    while i < skipword {
-        sum += u16::from_be(unsafe { *wdata.get_unchecked(i) }) as u32;
-        i += 1;
+    let word_count = len / 2;
+    assert!(skipword <= word_count);
+
+    let mut sum = 0u64;
+    let mut word = 0;
+    let mut pos = 0;
+
+    while pos + 4 <= len {
+        if word == skipword {
+            sum += u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as u64;
+        } else if word + 1 == skipword {
+            sum += u16::from_be_bytes([data[pos], data[pos + 1]]) as u64;
+        } else {
+            sum += u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as
+                   u64;
+        }
+        pos += 4;
+        word += 2;
     }
-    i += 1;
-    while i < wdata.len() {
-        sum += u16::from_be(unsafe { *wdata.get_unchecked(i) }) as u32;
-        i += 1;
+    // One trailing whole word, if any (the buffer has an odd number of words).
+    if pos + 2 <= len {
+        if word != skipword {
+            sum += u16::from_be_bytes([data[pos], data[pos + 1]]) as u64;
+        }
+        pos += 2;
     }
     // If the length is odd, make sure to checksum the final byte
     if len & 1 != 0 {
-        sum += (unsafe { *data.get_unchecked(len - 1) } as u32) << 8;
+        sum += (data[len - 1] as u64) << 8;
     }
+    debug_assert_eq!(pos + (len & 1), len);
 
     sum
 }
 
+/// Incrementally update a previously-computed Internet checksum to reflect a change to
+/// one 16-bit word of the data it covers, per RFC 1624, rather than recomputing the
+/// checksum over the whole packet. Given the old checksum `old_csum`, the old value of
+/// the word that changed `old_word`, and its new value `new_word`, returns the
+/// corrected checksum `HC' = ~(~HC + ~m + m')`, where `~` is one's complement and the
+/// additions fold end-around carries back into bit 0.
+///
+/// Note this does not special-case the `0x0000` UDP "no checksum" sentinel - `0x0000`
+/// is also a perfectly ordinary intermediate value when folding several word updates
+/// together, so callers that need to preserve the sentinel (eg.
+/// `update_udp_checksum_bytes`) must check for it once, up front, on the checksum the
+/// caller originally passed in.
+pub fn update_checksum(old_csum: u16be, old_word: u16, new_word: u16) -> u16be {
+    let mut sum = !old_csum as u64 + !old_word as u64 + new_word as u64;
+    while sum >> 16 != 0 {
+        sum = (sum >> 16) + (sum & 0xFFFF);
+    }
+    !(sum as u16)
+}
+
+/// Apply `update_checksum` word-by-word over two equal-length byte ranges, as when
+/// several contiguous fields of a packet are rewritten at once (eg. a NAT address and
+/// port rewrite). `old` and `new` must be the same length; if that length is odd, the
+/// trailing byte is treated the same way `sum_be_words` treats it - padded with a
+/// zero low byte.
+///
+/// `byte_offset` is where `old`/`new` start within the buffer the checksum was
+/// originally computed over, and must be even: the checksum sums 16-bit words, so a
+/// range starting mid-word would pair up bytes from two different real words and
+/// silently produce the wrong result.
+///
+/// This is protocol-agnostic and does not special-case a computed checksum of
+/// `0x0000` - unlike UDP, IPv4 and TCP have no reserved "no checksum" value, so
+/// `0x0000` is just an ordinary checksum for them. UDP callers that need to preserve
+/// the RFC 768 sentinel should use `update_udp_checksum_bytes` instead.
+pub fn update_checksum_bytes(old_csum: u16be, byte_offset: usize, old: &[u8], new: &[u8]) -> u16be {
+    assert_eq!(old.len(), new.len());
+    assert_eq!(byte_offset % 2,
+               0,
+               "update_checksum_bytes requires byte_offset to be word-aligned");
+
+    let mut csum = old_csum;
+    for (old_word, new_word) in old.chunks(2).zip(new.chunks(2)) {
+        csum = update_checksum(csum, be_word(old_word), be_word(new_word));
+    }
+    csum
+}
+
+/// Like `update_checksum_bytes`, but for UDP, where a stored checksum of `0x0000`
+/// means "no checksum in use" (RFC 768 has UDP remap a computed-zero checksum to
+/// `0xFFFF` on the wire, so the all-zero field never occurs for a checksum that's
+/// actually in use). That sentinel is checked once, on the checksum the caller passed
+/// in, and left untouched rather than folding any word updates into it.
+pub fn update_udp_checksum_bytes(old_csum: u16be, byte_offset: usize, old: &[u8], new: &[u8]) -> u16be {
+    if old_csum == 0 {
+        return old_csum;
+    }
+    update_checksum_bytes(old_csum, byte_offset, old, new)
+}
+
+fn be_word(chunk: &[u8]) -> u16 {
+    match chunk {
+        [hi, lo] => u16::from_be_bytes([*hi, *lo]),
+        [hi] => u16::from_be_bytes([*hi, 0]),
+        _ => unreachable!("chunks(2) never yields an empty or >2 byte slice"),
+    }
+}
+
+#[test]
+fn update_checksum_matches_full_recompute() {
+    let mut data = [0x45u8, 0x00, 0x00, 0x3c, 0x1c, 0x46, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00,
+                    0xc0, 0xa8, 0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7];
+    let original_csum = checksum(&data, 5, Checksum::Both);
+    data[10] = (original_csum >> 8) as u8;
+    data[11] = original_csum as u8;
+
+    // Rewrite the source address (bytes 12..16) as part of a NAT-style translation.
+    let old_source = [data[12], data[13], data[14], data[15]];
+    let new_source = [192, 168, 1, 55];
+
+    let updated = update_checksum_bytes(original_csum, 12, &old_source, &new_source);
+
+    data[10] = 0;
+    data[11] = 0;
+    data[12..16].copy_from_slice(&new_source);
+    let recomputed = checksum(&data, 5, Checksum::Both);
+
+    assert_eq!(updated, recomputed);
+}
+
+#[test]
+fn update_checksum_bytes_does_not_special_case_zero() {
+    // Unlike UDP, a plain IPv4/TCP-style update_checksum_bytes must treat a computed
+    // checksum of 0x0000 as an ordinary value, not a sentinel to leave untouched.
+    let old = [194u8, 144, 160, 27];
+    let new = [73u8, 179, 227, 84];
+    assert_eq!(update_checksum_bytes(0x0000, 0, &old, &new), 0x35a4);
+}
+
+#[test]
+fn update_udp_checksum_bytes_ignores_no_checksum_sentinel() {
+    assert_eq!(update_udp_checksum_bytes(0, 0, &[0x12, 0x34], &[0x56, 0x78]), 0);
+}
+
+#[test]
+fn update_checksum_bytes_survives_intermediate_zero() {
+    // The first word update alone drives the running checksum through 0x0000, which
+    // is an ordinary fold result, not the UDP "no checksum" sentinel - the second
+    // word's update must still be applied on top of it instead of being skipped.
+    let old = [0x00u8, 0x01, 0x00, 0xaa];
+    let new = [0x12u8, 0x35, 0x00, 0xbb];
+    assert_eq!(update_checksum(0x1234, 0x0001, 0x1235), 0x0000);
+
+    let updated = update_checksum_bytes(0x1234, 0, &old, &new);
+    assert_eq!(updated, 0xffee);
+}
+
+#[test]
+#[should_panic(expected = "word-aligned")]
+fn update_checksum_bytes_rejects_misaligned_offset() {
+    update_checksum_bytes(0x1234, 3, &[0x44], &[0x99]);
+}
+
 #[cfg(all(test, feature = "benchmark"))]
 mod checksum_benchmarks {
-    use super::checksum;
+    use super::{checksum, Checksum};
     use test::{Bencher, black_box};
 
     #[bench]
     fn bench_checksum_small(b: &mut Bencher) {
         let data = vec![99u8; 20];
-        b.iter(|| checksum(black_box(&data), 5));
+        b.iter(|| checksum(black_box(&data), 5, Checksum::Both));
     }
 
     #[bench]
     fn bench_checksum_large(b: &mut Bencher) {
         let data = vec![123u8; 1024];
-        b.iter(|| checksum(black_box(&data), 5));
+        b.iter(|| checksum(black_box(&data), 5, Checksum::Both));
     }
 }